@@ -0,0 +1,276 @@
+#![no_std]
+use soroban_sdk::{
+    contract, contractclient, contractimpl, contracttype, symbol_short, xdr::ToXdr, Address, BytesN,
+    Env, Val, Vec,
+};
+
+/// Storage keys for factory data
+#[contracttype]
+#[derive(Clone)]
+pub enum DataKey {
+    Admin,               // Address of factory admin
+    WasmHash,            // Uploaded PortfolioAgent code new agents are deployed from (BytesN<32>)
+    Registry,            // Agent registry new agents are recorded in (Address)
+    DeployCount,         // Number of agents deployed so far (u64)
+    Owned(Address),      // Agents deployed for an owner, persistent storage (Vec<Address>)
+}
+
+/// Caller-supplied settings for a new agent
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AgentConfig {
+    pub initial_score: u32,   // Starting strategy score, 0-1000
+}
+
+/// Initializer of a freshly deployed PortfolioAgent
+#[contractclient(name = "AgentClient")]
+pub trait Agent {
+    fn initialize(env: Env, admin: Address, initial_score: u32, initial_trades: u32);
+}
+
+/// Registration entrypoint of the agent registry
+///
+/// The returned snapshot is not used, so it is left undecoded.
+#[contractclient(name = "RegistryClient")]
+pub trait Registry {
+    fn register(env: Env, caller: Address, agent: Address) -> Val;
+}
+
+/// Highest initial score an agent can start with
+const MAX_SCORE: u32 = 1000;
+
+/// Ledgers per day at 5-second ledger close times
+const DAY_IN_LEDGERS: u32 = 17_280;
+
+/// Instance TTL left before it is extended again
+const INSTANCE_BUMP_THRESHOLD: u32 = 7 * DAY_IN_LEDGERS;
+
+/// Instance TTL after an extension
+const INSTANCE_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+
+/// Owner list TTL left before it is extended again
+const OWNED_BUMP_THRESHOLD: u32 = 30 * DAY_IN_LEDGERS;
+
+/// Owner list TTL after an extension
+const OWNED_BUMP_AMOUNT: u32 = 180 * DAY_IN_LEDGERS;
+
+#[contract]
+pub struct AgentFactory;
+
+#[contractimpl]
+impl AgentFactory {
+    /// Initialize the factory with its admin, the agent code and the registry
+    ///
+    /// The factory must be made the registry's registrar before agents can be
+    /// deployed.
+    pub fn initialize(env: Env, admin: Address, wasm_hash: BytesN<32>, registry: Address) {
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic!("Contract already initialized");
+        }
+        admin.require_auth();
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::WasmHash, &wasm_hash);
+        env.storage().instance().set(&DataKey::Registry, &registry);
+        Self::bump_instance(&env);
+    }
+
+    /// Deploy and initialize a new PortfolioAgent owned by `owner`
+    ///
+    /// Anyone may deploy an agent for themselves. `owner` becomes the agent's
+    /// admin, so it must authorize both this call and the agent's
+    /// `initialize`. The agent address depends only on `owner` and `salt`,
+    /// see `get_agent_address`. The agent is recorded in the registry and a
+    /// `created` event is emitted with the owner as second topic and
+    /// `(agent, config)` as data.
+    pub fn deploy(env: Env, owner: Address, salt: BytesN<32>, config: AgentConfig) -> Address {
+        owner.require_auth();
+        if config.initial_score > MAX_SCORE {
+            panic!("Initial score above 1000");
+        }
+        let wasm_hash: BytesN<32> = env.storage().instance()
+            .get(&DataKey::WasmHash)
+            .expect("Contract not initialized");
+        let agent = env.deployer()
+            .with_current_contract(Self::owner_salt(&env, &owner, &salt))
+            .deploy(wasm_hash);
+        AgentClient::new(&env, &agent).initialize(&owner, &config.initial_score, &0);
+        RegistryClient::new(&env, &Self::get_registry(env.clone()))
+            .register(&env.current_contract_address(), &agent);
+
+        let key = DataKey::Owned(owner.clone());
+        let mut owned = Self::get_agents_of(env.clone(), owner.clone());
+        owned.push_back(agent.clone());
+        env.storage().persistent().set(&key, &owned);
+        env.storage().persistent().extend_ttl(&key, OWNED_BUMP_THRESHOLD, OWNED_BUMP_AMOUNT);
+        let count = Self::get_deploy_count(env.clone()) + 1;
+        env.storage().instance().set(&DataKey::DeployCount, &count);
+        Self::bump_instance(&env);
+
+        env.events().publish((symbol_short!("created"), owner), (agent.clone(), config));
+        agent
+    }
+
+    /// Replace the code new agents are deployed from (admin only)
+    ///
+    /// Agents already deployed keep their code.
+    pub fn set_wasm_hash(env: Env, caller: Address, wasm_hash: BytesN<32>) {
+        let admin = Self::get_admin(env.clone());
+        if caller != admin {
+            panic!("Unauthorized: only admin can call this function");
+        }
+        caller.require_auth();
+        env.storage().instance().set(&DataKey::WasmHash, &wasm_hash);
+        Self::bump_instance(&env);
+
+        env.events().publish((symbol_short!("wasm"),), wasm_hash);
+    }
+
+    /// Address an agent deployed for `owner` with `salt` gets
+    pub fn get_agent_address(env: Env, owner: Address, salt: BytesN<32>) -> Address {
+        env.deployer()
+            .with_current_contract(Self::owner_salt(&env, &owner, &salt))
+            .deployed_address()
+    }
+
+    /// Agents deployed for `owner`, oldest first
+    pub fn get_agents_of(env: Env, owner: Address) -> Vec<Address> {
+        env.storage().persistent()
+            .get(&DataKey::Owned(owner))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Number of agents deployed so far
+    pub fn get_deploy_count(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::DeployCount).unwrap_or(0)
+    }
+
+    /// Code new agents are deployed from
+    pub fn get_wasm_hash(env: Env) -> BytesN<32> {
+        env.storage().instance()
+            .get(&DataKey::WasmHash)
+            .expect("Contract not initialized")
+    }
+
+    /// Registry new agents are recorded in
+    pub fn get_registry(env: Env) -> Address {
+        env.storage().instance()
+            .get(&DataKey::Registry)
+            .expect("Contract not initialized")
+    }
+
+    /// Get the factory admin
+    pub fn get_admin(env: Env) -> Address {
+        env.storage().instance()
+            .get(&DataKey::Admin)
+            .expect("Contract not initialized")
+    }
+
+    /// Internal: Deployment salt scoped to `owner`
+    ///
+    /// Hashing the owner in keeps one caller from taking the address another
+    /// caller's salt would deploy to.
+    fn owner_salt(env: &Env, owner: &Address, salt: &BytesN<32>) -> BytesN<32> {
+        let mut preimage = owner.clone().to_xdr(env);
+        preimage.append(&salt.clone().into());
+        env.crypto().sha256(&preimage).to_bytes()
+    }
+
+    /// Internal: Keep the factory instance alive
+    fn bump_instance(env: &Env) {
+        env.storage().instance().extend_ttl(INSTANCE_BUMP_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+    use std::rc::Rc;
+
+    use agent_registry::{AgentRegistry, AgentRegistryClient};
+    use portfolio_agent::{PortfolioAgent, PortfolioAgentClient};
+    use soroban_sdk::{
+        testutils::{Address as _, Events},
+        xdr, Bytes, IntoVal, TryFromVal,
+    };
+
+    /// Make `address` run the native PortfolioAgent once the factory deploys there
+    ///
+    /// Tests have no agent wasm to upload. Registering the agent natively
+    /// stores an instance running the empty test wasm, and dropping that
+    /// instance again leaves the deployment to the factory while calls to the
+    /// address still dispatch to the native contract.
+    fn expect_native_agent(env: &Env, address: &Address) {
+        env.register_contract(address, PortfolioAgent);
+        let key = Rc::new(xdr::LedgerKey::ContractData(xdr::LedgerKeyContractData {
+            contract: address.into(),
+            key: xdr::ScVal::LedgerKeyContractInstance,
+            durability: xdr::ContractDataDurability::Persistent,
+        }));
+        let budget = env.host().budget_cloned();
+        env.host().with_mut_storage(|storage| storage.del(&key, &budget)).unwrap();
+    }
+
+    fn setup(env: &Env) -> (AgentFactoryClient<'_>, AgentRegistryClient<'_>, Address) {
+        env.mock_all_auths();
+        let admin = Address::generate(env);
+        let registry = AgentRegistryClient::new(env, &env.register_contract(None, AgentRegistry));
+        registry.initialize(&admin);
+        let factory = AgentFactoryClient::new(env, &env.register_contract(None, AgentFactory));
+        // Native test contracts run from the empty wasm
+        let wasm_hash = env.crypto().sha256(&Bytes::new(env)).to_bytes();
+        factory.initialize(&admin, &wasm_hash, &registry.address);
+        registry.set_registrar(&admin, &Some(factory.address.clone()));
+        (factory, registry, admin)
+    }
+
+    #[test]
+    fn test_deploy_initializes_and_registers_agent() {
+        let env = Env::default();
+        let (factory, registry, _) = setup(&env);
+        let owner = Address::generate(&env);
+        let salt = BytesN::from_array(&env, &[7; 32]);
+        let expected = factory.get_agent_address(&owner, &salt);
+        // The same salt gives another owner a different address
+        assert_ne!(factory.get_agent_address(&Address::generate(&env), &salt), expected);
+        expect_native_agent(&env, &expected);
+
+        let config = AgentConfig { initial_score: 640 };
+        let agent = factory.deploy(&owner, &salt, &config);
+        assert_eq!(agent, expected);
+        let (contract, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(contract, factory.address);
+        assert_eq!(topics, (symbol_short!("created"), owner.clone()).into_val(&env));
+        assert_eq!(<(Address, AgentConfig)>::try_from_val(&env, &data).unwrap(), (agent.clone(), config.clone()));
+
+        let metrics = PortfolioAgentClient::new(&env, &agent).get_portfolio_metrics();
+        assert_eq!((metrics.admin, metrics.score, metrics.total_trades), (owner.clone(), 640, 0));
+        assert_eq!(registry.get_agents(), soroban_sdk::vec![&env, agent.clone()]);
+        assert_eq!(registry.get_snapshot(&agent).unwrap().score, 640);
+        assert_eq!(factory.get_agents_of(&owner), soroban_sdk::vec![&env, agent]);
+        assert_eq!(factory.get_deploy_count(), 1);
+
+        // A salt cannot be reused by the same owner
+        assert!(factory.try_deploy(&owner, &salt, &config).is_err());
+    }
+
+    #[test]
+    fn test_deploy_validation_and_admin_controls() {
+        let env = Env::default();
+        let (factory, registry, admin) = setup(&env);
+        let owner = Address::generate(&env);
+        let salt = BytesN::from_array(&env, &[1; 32]);
+        expect_native_agent(&env, &factory.get_agent_address(&owner, &salt));
+        assert!(factory.try_deploy(&owner, &salt, &AgentConfig { initial_score: 1001 }).is_err());
+
+        // Without the registrar role nothing is deployed
+        registry.set_registrar(&admin, &None);
+        assert!(factory.try_deploy(&owner, &salt, &AgentConfig { initial_score: 500 }).is_err());
+        assert_eq!(factory.get_deploy_count(), 0);
+
+        let hash = BytesN::from_array(&env, &[9; 32]);
+        assert!(factory.try_set_wasm_hash(&owner, &hash).is_err());
+        factory.set_wasm_hash(&admin, &hash);
+        assert_eq!(factory.get_wasm_hash(), hash);
+    }
+}
@@ -0,0 +1,449 @@
+#![no_std]
+use soroban_sdk::{
+    contract, contractclient, contractimpl, contracttype, symbol_short, vec, Address, Env, Map, Vec,
+};
+
+/// Storage keys for registry data
+#[contracttype]
+#[derive(Clone)]
+pub enum DataKey {
+    Admin,               // Address of registry admin
+    Agents,              // Registered agents in registration order (Vec<Address>)
+    Snapshots,           // Last reading taken from each agent (Map<Address, AgentSnapshot>)
+    Registrar,           // Contract allowed to register agents besides the admin, e.g. a factory (Address)
+}
+
+/// Read requested from an agent's `get_many`
+///
+/// Mirrors the `Score` and `Nav` variants of the agent's `QueryKind`. Enum
+/// values are encoded by variant name, so only the variants the registry asks
+/// for need to be declared.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AgentQuery {
+    Score,
+    Nav,
+}
+
+/// Result of an `AgentQuery`, mirroring the agent's `QueryResult`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AgentReading {
+    Score(u32),
+    Nav(i128, u64),           // Total value and valuation time, both 0 if never valued
+}
+
+/// Read interface of a PortfolioAgent used by the registry
+#[contractclient(name = "AgentClient")]
+pub trait Agent {
+    fn get_many(env: Env, queries: Vec<AgentQuery>) -> Vec<AgentReading>;
+}
+
+/// Last reading of one registered agent
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AgentSnapshot {
+    pub agent: Address,
+    pub score: u32,
+    pub tvl: i128,            // Total value of the agent's last cached valuation
+    pub valued_at: u64,       // Time of that valuation, 0 if never valued
+    pub refreshed_at: u64,    // When the registry last read the agent
+}
+
+/// Order of a ranked query
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ranking {
+    Score,
+    Tvl,
+}
+
+/// Aggregate over all registered agents
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegistryTotals {
+    pub agents: u32,
+    pub total_tvl: i128,
+    pub average_score: u32,   // Rounded down, 0 without agents
+}
+
+/// Maximum number of registered agents
+const MAX_AGENTS: u32 = 100;
+
+/// Maximum number of agents read by one `refresh` call
+const MAX_REFRESH: u32 = 25;
+
+/// Ledgers per day at 5-second ledger close times
+const DAY_IN_LEDGERS: u32 = 17_280;
+
+/// Instance TTL left before it is extended again
+const INSTANCE_BUMP_THRESHOLD: u32 = 7 * DAY_IN_LEDGERS;
+
+/// Instance TTL after an extension
+const INSTANCE_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+
+#[contract]
+pub struct AgentRegistry;
+
+#[contractimpl]
+impl AgentRegistry {
+    /// Initialize the registry with its admin
+    pub fn initialize(env: Env, admin: Address) {
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic!("Contract already initialized");
+        }
+        admin.require_auth();
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        Self::bump_instance(&env);
+    }
+
+    /// Add a PortfolioAgent to the registry (admin or registrar)
+    ///
+    /// The agent is read once on registration, so an address that does not
+    /// answer the agent's `get_many` is rejected.
+    pub fn register(env: Env, caller: Address, agent: Address) -> AgentSnapshot {
+        if Some(caller.clone()) != Self::get_registrar(env.clone()) && caller != Self::get_admin(env.clone()) {
+            panic!("Unauthorized: only admin or registrar can register agents");
+        }
+        caller.require_auth();
+        let mut agents = Self::get_agents(env.clone());
+        if agents.contains(&agent) {
+            panic!("Agent already registered");
+        }
+        if agents.len() >= MAX_AGENTS {
+            panic!("Too many agents");
+        }
+        let snapshot = match Self::read_agent(&env, &agent) {
+            Some(snapshot) => snapshot,
+            None => panic!("Not a portfolio agent"),
+        };
+        agents.push_back(agent.clone());
+        env.storage().instance().set(&DataKey::Agents, &agents);
+        let mut snapshots = Self::load_snapshots(&env);
+        snapshots.set(agent.clone(), snapshot.clone());
+        env.storage().instance().set(&DataKey::Snapshots, &snapshots);
+        Self::bump_instance(&env);
+
+        env.events().publish((symbol_short!("register"),), agent);
+        snapshot
+    }
+
+    /// Remove an agent from the registry (admin only)
+    pub fn unregister(env: Env, caller: Address, agent: Address) {
+        Self::require_admin(&env, &caller);
+        let mut agents = Self::get_agents(env.clone());
+        let index = match agents.first_index_of(&agent) {
+            Some(index) => index,
+            None => panic!("Agent not registered"),
+        };
+        agents.remove(index);
+        env.storage().instance().set(&DataKey::Agents, &agents);
+        let mut snapshots = Self::load_snapshots(&env);
+        snapshots.remove(agent.clone());
+        env.storage().instance().set(&DataKey::Snapshots, &snapshots);
+        Self::bump_instance(&env);
+
+        env.events().publish((symbol_short!("unreg"),), agent);
+    }
+
+    /// Re-read up to `limit` agents starting at registration index `start`
+    ///
+    /// Anyone may call this, so keepers can page through the registry. An
+    /// agent whose read fails keeps its previous snapshot and is reported with
+    /// a `stale` event. Returns the number of agents refreshed.
+    pub fn refresh(env: Env, start: u32, limit: u32) -> u32 {
+        if limit > MAX_REFRESH {
+            panic!("Too many agents per refresh");
+        }
+        let agents = Self::get_agents(env.clone());
+        let end = start.saturating_add(limit).min(agents.len());
+        let mut snapshots = Self::load_snapshots(&env);
+        let mut refreshed = 0;
+        for index in start..end {
+            let agent = agents.get_unchecked(index);
+            match Self::read_agent(&env, &agent) {
+                Some(snapshot) => {
+                    snapshots.set(agent, snapshot);
+                    refreshed += 1;
+                }
+                None => env.events().publish((symbol_short!("stale"),), agent),
+            }
+        }
+        env.storage().instance().set(&DataKey::Snapshots, &snapshots);
+        Self::bump_instance(&env);
+        refreshed
+    }
+
+    /// Registered agents in registration order
+    pub fn get_agents(env: Env) -> Vec<Address> {
+        env.storage().instance()
+            .get(&DataKey::Agents)
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Last snapshot of `agent`, if registered
+    pub fn get_snapshot(env: Env, agent: Address) -> Option<AgentSnapshot> {
+        Self::load_snapshots(&env).get(agent)
+    }
+
+    /// Up to `limit` snapshots, highest first by `by`
+    ///
+    /// Ties keep registration order.
+    pub fn get_ranked(env: Env, by: Ranking, limit: u32) -> Vec<AgentSnapshot> {
+        let snapshots = Self::load_snapshots(&env);
+        let mut ranked: Vec<AgentSnapshot> = Vec::new(&env);
+        for agent in Self::get_agents(env.clone()).iter() {
+            let snapshot = snapshots.get_unchecked(agent);
+            let key = Self::rank_key(&snapshot, by);
+            let mut index = ranked.len();
+            while index > 0 && Self::rank_key(&ranked.get_unchecked(index - 1), by) < key {
+                index -= 1;
+            }
+            if index < limit {
+                ranked.insert(index, snapshot);
+                if ranked.len() > limit {
+                    ranked.pop_back();
+                }
+            }
+        }
+        ranked
+    }
+
+    /// Agent count, summed TVL and mean score over all snapshots
+    pub fn get_totals(env: Env) -> RegistryTotals {
+        let mut total_tvl: i128 = 0;
+        let mut score_sum: u64 = 0;
+        let snapshots = Self::load_snapshots(&env);
+        for snapshot in snapshots.values().iter() {
+            total_tvl = match total_tvl.checked_add(snapshot.tvl) {
+                Some(total) => total,
+                None => panic!("Total value overflow"),
+            };
+            score_sum += snapshot.score as u64;
+        }
+        let agents = snapshots.len();
+        RegistryTotals {
+            agents,
+            total_tvl,
+            average_score: if agents == 0 { 0 } else { (score_sum / agents as u64) as u32 },
+        }
+    }
+
+    /// Allow `registrar` to register agents, or revoke the role with `None` (admin only)
+    pub fn set_registrar(env: Env, caller: Address, registrar: Option<Address>) {
+        Self::require_admin(&env, &caller);
+        match &registrar {
+            Some(registrar) => env.storage().instance().set(&DataKey::Registrar, registrar),
+            None => env.storage().instance().remove(&DataKey::Registrar),
+        }
+        env.events().publish((symbol_short!("registrar"),), registrar);
+    }
+
+    /// Contract allowed to register agents besides the admin
+    pub fn get_registrar(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Registrar)
+    }
+
+    /// Get the registry admin
+    pub fn get_admin(env: Env) -> Address {
+        env.storage().instance()
+            .get(&DataKey::Admin)
+            .expect("Contract not initialized")
+    }
+
+    /// Internal: Require `caller` to be the admin and to have authorized the call
+    fn require_admin(env: &Env, caller: &Address) {
+        if *caller != Self::get_admin(env.clone()) {
+            panic!("Unauthorized: only admin can call this function");
+        }
+        caller.require_auth();
+    }
+
+    /// Internal: Score and TVL of `agent` in one `get_many` call, `None` if the call fails
+    fn read_agent(env: &Env, agent: &Address) -> Option<AgentSnapshot> {
+        let queries = vec![env, AgentQuery::Score, AgentQuery::Nav];
+        let readings = match AgentClient::new(env, agent).try_get_many(&queries) {
+            Ok(Ok(readings)) => readings,
+            _ => return None,
+        };
+        match (readings.get(0), readings.get(1)) {
+            (Some(AgentReading::Score(score)), Some(AgentReading::Nav(tvl, valued_at))) => Some(AgentSnapshot {
+                agent: agent.clone(),
+                score,
+                tvl,
+                valued_at,
+                refreshed_at: env.ledger().timestamp(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Internal: Value a snapshot is ranked by
+    fn rank_key(snapshot: &AgentSnapshot, by: Ranking) -> i128 {
+        match by {
+            Ranking::Score => snapshot.score as i128,
+            Ranking::Tvl => snapshot.tvl,
+        }
+    }
+
+    /// Internal: Snapshots of all registered agents
+    fn load_snapshots(env: &Env) -> Map<Address, AgentSnapshot> {
+        env.storage().instance()
+            .get(&DataKey::Snapshots)
+            .unwrap_or(Map::new(env))
+    }
+
+    /// Internal: Keep the registry instance alive
+    fn bump_instance(env: &Env) {
+        env.storage().instance().extend_ttl(INSTANCE_BUMP_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+
+    use portfolio_agent::{PortfolioAgent, PortfolioAgentClient};
+    use soroban_sdk::testutils::{Address as _, Ledger};
+
+    const START_TIME: u64 = 1_700_000_000;
+
+    /// Test agent answering `get_many` with settable readings, or failing when told to
+    #[contract]
+    pub struct MockAgent;
+
+    #[contractimpl]
+    impl MockAgent {
+        pub fn set_reading(env: Env, score: u32, tvl: i128) {
+            env.storage().instance().set(&symbol_short!("score"), &score);
+            env.storage().instance().set(&symbol_short!("tvl"), &tvl);
+        }
+
+        pub fn set_failing(env: Env, failing: bool) {
+            env.storage().instance().set(&symbol_short!("failing"), &failing);
+        }
+
+        pub fn get_many(env: Env, queries: Vec<AgentQuery>) -> Vec<AgentReading> {
+            if env.storage().instance().get(&symbol_short!("failing")).unwrap_or(false) {
+                panic!("agent unavailable");
+            }
+            let score: u32 = env.storage().instance().get(&symbol_short!("score")).unwrap_or(0);
+            let tvl: i128 = env.storage().instance().get(&symbol_short!("tvl")).unwrap_or(0);
+            let mut readings = Vec::new(&env);
+            for query in queries.iter() {
+                readings.push_back(match query {
+                    AgentQuery::Score => AgentReading::Score(score),
+                    AgentQuery::Nav => AgentReading::Nav(tvl, env.ledger().timestamp()),
+                });
+            }
+            readings
+        }
+    }
+
+    fn setup(env: &Env) -> (AgentRegistryClient<'_>, Address) {
+        env.mock_all_auths();
+        env.ledger().with_mut(|ledger| ledger.timestamp = START_TIME);
+        let client = AgentRegistryClient::new(env, &env.register_contract(None, AgentRegistry));
+        let admin = Address::generate(env);
+        client.initialize(&admin);
+        (client, admin)
+    }
+
+    fn mock_agent(env: &Env, score: u32, tvl: i128) -> Address {
+        let agent = env.register_contract(None, MockAgent);
+        MockAgentClient::new(env, &agent).set_reading(&score, &tvl);
+        agent
+    }
+
+    #[test]
+    fn test_register_reads_portfolio_agent() {
+        let env = Env::default();
+        let (client, admin) = setup(&env);
+        let agent = env.register_contract(None, PortfolioAgent);
+        PortfolioAgentClient::new(&env, &agent).initialize(&admin, &870, &0);
+
+        let snapshot = client.register(&admin, &agent);
+        assert_eq!(snapshot, AgentSnapshot {
+            agent: agent.clone(),
+            score: 870,
+            tvl: 0,
+            valued_at: 0,
+            refreshed_at: START_TIME,
+        });
+        assert_eq!(client.get_agents(), vec![&env, agent.clone()]);
+        assert_eq!(client.get_snapshot(&agent), Some(snapshot));
+
+        // Duplicates and contracts that are not agents are refused
+        assert!(client.try_register(&admin, &agent).is_err());
+        let not_agent = env.register_contract(None, AgentRegistry);
+        assert!(client.try_register(&admin, &not_agent).is_err());
+        assert!(client.try_register(&Address::generate(&env), &mock_agent(&env, 1, 1)).is_err());
+
+        // A registrar may register but not unregister
+        let registrar = Address::generate(&env);
+        client.set_registrar(&admin, &Some(registrar.clone()));
+        let other = mock_agent(&env, 1, 1);
+        client.register(&registrar, &other);
+        assert!(client.try_unregister(&registrar, &other).is_err());
+        client.set_registrar(&admin, &None);
+        assert!(client.try_register(&registrar, &mock_agent(&env, 2, 2)).is_err());
+        client.unregister(&admin, &other);
+
+        client.unregister(&admin, &agent);
+        assert_eq!(client.get_agents().len(), 0);
+        assert_eq!(client.get_snapshot(&agent), None);
+    }
+
+    #[test]
+    fn test_ranked_queries_and_totals() {
+        let env = Env::default();
+        let (client, admin) = setup(&env);
+        let a = mock_agent(&env, 700, 5_000);
+        let b = mock_agent(&env, 900, 1_000);
+        let c = mock_agent(&env, 800, 9_000);
+        let d = mock_agent(&env, 900, 2_000);
+        for agent in [&a, &b, &c, &d] {
+            client.register(&admin, agent);
+        }
+
+        let order = |by: Ranking, limit: u32| -> std::vec::Vec<Address> {
+            client.get_ranked(&by, &limit).iter().map(|snapshot| snapshot.agent).collect()
+        };
+        // Equal scores keep registration order
+        assert_eq!(order(Ranking::Score, 10), [b.clone(), d.clone(), c.clone(), a.clone()]);
+        assert_eq!(order(Ranking::Tvl, 2), [c.clone(), a.clone()]);
+        assert_eq!(order(Ranking::Tvl, 0), []);
+
+        assert_eq!(client.get_totals(), RegistryTotals {
+            agents: 4,
+            total_tvl: 17_000,
+            average_score: 825,
+        });
+    }
+
+    #[test]
+    fn test_refresh_keeps_failing_agent_snapshot() {
+        let env = Env::default();
+        let (client, admin) = setup(&env);
+        let healthy = mock_agent(&env, 500, 100);
+        let failing = mock_agent(&env, 600, 200);
+        client.register(&admin, &healthy);
+        client.register(&admin, &failing);
+
+        env.ledger().with_mut(|ledger| ledger.timestamp = START_TIME + 60);
+        MockAgentClient::new(&env, &healthy).set_reading(&550, &300);
+        MockAgentClient::new(&env, &failing).set_reading(&10, &0);
+        MockAgentClient::new(&env, &failing).set_failing(&true);
+
+        assert_eq!(client.refresh(&0, &10), 1);
+        let snapshot = client.get_snapshot(&healthy).unwrap();
+        assert_eq!((snapshot.score, snapshot.tvl, snapshot.refreshed_at), (550, 300, START_TIME + 60));
+        let stale = client.get_snapshot(&failing).unwrap();
+        assert_eq!((stale.score, stale.tvl, stale.refreshed_at), (600, 200, START_TIME));
+
+        // Pages past the end read nothing; oversized pages are refused
+        assert_eq!(client.refresh(&2, &10), 0);
+        assert!(client.try_refresh(&0, &(MAX_REFRESH + 1)).is_err());
+    }
+}
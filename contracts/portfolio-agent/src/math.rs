@@ -0,0 +1,176 @@
+//! Checked fixed-point arithmetic
+//!
+//! Amounts are `i128`. Prices carry `PRICE_SCALE` (7 decimals) and ratios are
+//! basis points out of `BPS_DENOMINATOR`. Every operation reports overflow or
+//! a zero divisor as a typed `Error` instead of wrapping or saturating, and
+//! every division rounds toward zero.
+
+use crate::{Error, BPS_DENOMINATOR, PRICE_SCALE, SCORE_SCALE};
+
+/// Basis-point denominator as `i128`
+const BPS: i128 = BPS_DENOMINATOR as i128;
+
+/// `a + b`
+pub fn add(a: i128, b: i128) -> Result<i128, Error> {
+    a.checked_add(b).ok_or(Error::MathOverflow)
+}
+
+/// `a - b`
+pub fn sub(a: i128, b: i128) -> Result<i128, Error> {
+    a.checked_sub(b).ok_or(Error::MathOverflow)
+}
+
+/// `a * b`
+pub fn mul(a: i128, b: i128) -> Result<i128, Error> {
+    a.checked_mul(b).ok_or(Error::MathOverflow)
+}
+
+/// `a * b / denominator`, rounded toward zero
+pub fn mul_div(a: i128, b: i128, denominator: i128) -> Result<i128, Error> {
+    if denominator == 0 {
+        return Err(Error::DivisionByZero);
+    }
+    mul(a, b)?.checked_div(denominator).ok_or(Error::MathOverflow)
+}
+
+/// Value of `amount` units at a `PRICE_SCALE` price
+pub fn value(amount: i128, price: i128) -> Result<i128, Error> {
+    mul_div(amount, price, PRICE_SCALE)
+}
+
+/// Units worth `value` at a `PRICE_SCALE` price
+pub fn units(value: i128, price: i128) -> Result<i128, Error> {
+    mul_div(value, PRICE_SCALE, price)
+}
+
+/// `bps` basis points of `amount`
+pub fn apply_bps(amount: i128, bps: u32) -> Result<i128, Error> {
+    mul_div(amount, bps as i128, BPS)
+}
+
+/// `part` expressed in basis points of `whole`
+pub fn to_bps(part: i128, whole: i128) -> Result<i128, Error> {
+    mul_div(part, BPS, whole)
+}
+
+/// Quantity-weighted average of two prices
+pub fn weighted_price(quantity_a: i128, price_a: i128, quantity_b: i128, price_b: i128) -> Result<i128, Error> {
+    let total = add(quantity_a, quantity_b)?;
+    let sum = add(mul(quantity_a, price_a)?, mul(quantity_b, price_b)?)?;
+    if total == 0 {
+        return Err(Error::DivisionByZero);
+    }
+    sum.checked_div(total).ok_or(Error::MathOverflow)
+}
+
+/// `count + 1` for an unsigned counter
+pub fn increment<T: Into<u64> + TryFrom<u64>>(count: T) -> Result<T, Error> {
+    let next = count.into().checked_add(1).ok_or(Error::MathOverflow)?;
+    T::try_from(next).map_err(|_| Error::MathOverflow)
+}
+
+/// Score after applying a performance metric
+///
+/// A positive metric raises the score by `positive` per 1000 points and a
+/// negative one lowers it by `negative` per 1000 points. The result is
+/// clamped between 0 and 1000; that clamp is intended, not an overflow.
+pub fn adjust_score(current: u32, metric: i32, positive: u32, negative: u32) -> Result<u32, Error> {
+    let factor = if metric >= 0 { positive } else { negative };
+    let delta = mul_div(metric as i128, factor as i128, SCORE_SCALE as i128)?;
+    let score = add(current as i128, delta)?;
+    Ok(score.clamp(0, SCORE_SCALE as i128) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_and_mul_report_overflow() {
+        assert_eq!(add(2, 3), Ok(5));
+        assert_eq!(add(i128::MAX, 0), Ok(i128::MAX));
+        assert_eq!(add(i128::MAX, 1), Err(Error::MathOverflow));
+        assert_eq!(add(i128::MIN, -1), Err(Error::MathOverflow));
+        assert_eq!(sub(2, 3), Ok(-1));
+        assert_eq!(sub(i128::MIN, 1), Err(Error::MathOverflow));
+
+        assert_eq!(mul(-4, 5), Ok(-20));
+        assert_eq!(mul(i128::MAX, 2), Err(Error::MathOverflow));
+        assert_eq!(mul(i128::MIN, -1), Err(Error::MathOverflow));
+    }
+
+    #[test]
+    fn test_increment_reports_overflow() {
+        assert_eq!(increment(7u32), Ok(8));
+        assert_eq!(increment(u32::MAX), Err(Error::MathOverflow));
+        assert_eq!(increment(u64::MAX), Err(Error::MathOverflow));
+    }
+
+    #[test]
+    fn test_mul_div_rounds_toward_zero() {
+        assert_eq!(mul_div(7, 3, 2), Ok(10));
+        assert_eq!(mul_div(-7, 3, 2), Ok(-10));
+        assert_eq!(mul_div(7, -3, -2), Ok(10));
+        assert_eq!(mul_div(1, 1, 3), Ok(0));
+        assert_eq!(mul_div(-1, 1, 3), Ok(0));
+    }
+
+    #[test]
+    fn test_mul_div_reports_errors() {
+        assert_eq!(mul_div(1, 1, 0), Err(Error::DivisionByZero));
+        assert_eq!(mul_div(i128::MAX, 2, 2), Err(Error::MathOverflow));
+        assert_eq!(mul_div(i128::MIN, 1, -1), Err(Error::MathOverflow));
+        // The product must fit even when the quotient would
+        assert_eq!(mul_div(i128::MAX / 2 + 1, 2, 4), Err(Error::MathOverflow));
+    }
+
+    #[test]
+    fn test_value_and_units_use_price_scale() {
+        assert_eq!(value(100, PRICE_SCALE / 2), Ok(50));
+        assert_eq!(value(3, PRICE_SCALE / 2), Ok(1));
+        assert_eq!(value(-3, PRICE_SCALE / 2), Ok(-1));
+        assert_eq!(value(i128::MAX, PRICE_SCALE), Err(Error::MathOverflow));
+
+        assert_eq!(units(50, PRICE_SCALE / 2), Ok(100));
+        assert_eq!(units(1, 3 * PRICE_SCALE), Ok(0));
+        assert_eq!(units(50, 0), Err(Error::DivisionByZero));
+    }
+
+    #[test]
+    fn test_basis_points() {
+        assert_eq!(apply_bps(1_000, 2_500), Ok(250));
+        assert_eq!(apply_bps(1_000, 0), Ok(0));
+        assert_eq!(apply_bps(1_000, BPS_DENOMINATOR), Ok(1_000));
+        assert_eq!(apply_bps(3, 5_000), Ok(1));
+        assert_eq!(apply_bps(i128::MAX, 2), Err(Error::MathOverflow));
+
+        assert_eq!(to_bps(250, 1_000), Ok(2_500));
+        assert_eq!(to_bps(-1, 3), Ok(-3_333));
+        assert_eq!(to_bps(1, 0), Err(Error::DivisionByZero));
+    }
+
+    #[test]
+    fn test_weighted_price() {
+        assert_eq!(weighted_price(100, 10, 100, 20), Ok(15));
+        assert_eq!(weighted_price(0, 0, 50, 7), Ok(7));
+        assert_eq!(weighted_price(2, 1, 1, 2), Ok(1));
+        assert_eq!(weighted_price(0, 10, 0, 20), Err(Error::DivisionByZero));
+        assert_eq!(weighted_price(i128::MAX, 2, 1, 1), Err(Error::MathOverflow));
+        assert_eq!(weighted_price(i128::MAX, 1, 1, 1), Err(Error::MathOverflow));
+    }
+
+    #[test]
+    fn test_adjust_score() {
+        assert_eq!(adjust_score(870, 10_000, 5, 3), Ok(920));
+        assert_eq!(adjust_score(870, -10_000, 5, 3), Ok(840));
+        assert_eq!(adjust_score(870, 0, 5, 3), Ok(870));
+        // Partial steps round toward zero in both directions
+        assert_eq!(adjust_score(870, 199, 5, 3), Ok(870));
+        assert_eq!(adjust_score(870, -333, 5, 3), Ok(870));
+        // Clamped to the score range
+        assert_eq!(adjust_score(990, 10_000, 5, 3), Ok(1000));
+        assert_eq!(adjust_score(10, -10_000, 5, 3), Ok(0));
+        assert_eq!(adjust_score(500, i32::MAX, u32::MAX, 0), Ok(1000));
+        assert_eq!(adjust_score(500, i32::MIN, 0, u32::MAX), Ok(0));
+    }
+}
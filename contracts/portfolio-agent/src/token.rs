@@ -0,0 +1,90 @@
+//! Calls to Stellar Asset Contract tokens
+//!
+//! Every balance read and transfer of an external token goes through here.
+//! Each call holds the re-entry lock and reports a failing token as
+//! `Error::TokenCallFailed` instead of the token's own error. Token decimals
+//! are read once and cached, so amounts can be normalized to the
+//! `INTERNAL_DECIMALS` scale that prices and valuations assume.
+
+use soroban_sdk::{panic_with_error, token::TokenClient, Address, Env, Map};
+
+use crate::{math, Error, PortfolioAgent, StateKey};
+
+/// Decimals of the internal amount scale, matching `PRICE_SCALE`
+pub const INTERNAL_DECIMALS: u32 = 7;
+
+/// Balance of `id` in `token`
+pub fn balance(env: &Env, token: &Address, id: &Address) -> i128 {
+    match PortfolioAgent::guarded(env, || TokenClient::new(env, token).try_balance(id)) {
+        Ok(Ok(balance)) => balance,
+        _ => panic_with_error!(env, Error::TokenCallFailed),
+    }
+}
+
+/// Move `amount` of `token` from `from` to `to`
+pub fn transfer(env: &Env, token: &Address, from: &Address, to: &Address, amount: i128) {
+    if PortfolioAgent::guarded(env, || TokenClient::new(env, token).try_transfer(from, to, &amount)).is_err() {
+        panic_with_error!(env, Error::TokenCallFailed);
+    }
+}
+
+/// Decimals of `token`, read from the token once and then cached
+pub fn decimals(env: &Env, token: &Address) -> u32 {
+    let mut cached: Map<Address, u32> = env.storage().instance()
+        .get(&StateKey::TokenDecimals)
+        .unwrap_or(Map::new(env));
+    if let Some(decimals) = cached.get(token.clone()) {
+        return decimals;
+    }
+    let decimals = match PortfolioAgent::guarded(env, || TokenClient::new(env, token).try_decimals()) {
+        Ok(Ok(decimals)) => decimals,
+        _ => panic_with_error!(env, Error::TokenCallFailed),
+    };
+    cached.set(token.clone(), decimals);
+    env.storage().instance().set(&StateKey::TokenDecimals, &cached);
+    decimals
+}
+
+/// `amount` of `token` in its own units, expressed at `INTERNAL_DECIMALS`
+pub fn to_internal(env: &Env, token: &Address, amount: i128) -> i128 {
+    if amount == 0 {
+        return 0;
+    }
+    PortfolioAgent::checked(env, rescale(amount, decimals(env, token), INTERNAL_DECIMALS))
+}
+
+/// An `INTERNAL_DECIMALS` amount expressed in units of `token`, rounded toward zero
+pub fn from_internal(env: &Env, token: &Address, amount: i128) -> i128 {
+    if amount == 0 {
+        return 0;
+    }
+    PortfolioAgent::checked(env, rescale(amount, INTERNAL_DECIMALS, decimals(env, token)))
+}
+
+/// `amount` moved from `from` to `to` decimals, rounded toward zero
+pub fn rescale(amount: i128, from: u32, to: u32) -> Result<i128, Error> {
+    let factor = |exponent: u32| 10i128.checked_pow(exponent).ok_or(Error::MathOverflow);
+    if to >= from {
+        math::mul(amount, factor(to - from)?)
+    } else {
+        math::mul_div(amount, 1, factor(from - to)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rescale() {
+        assert_eq!(rescale(12_345, 7, 7), Ok(12_345));
+        assert_eq!(rescale(5, 6, 7), Ok(50));
+        assert_eq!(rescale(1, 0, 7), Ok(10_000_000));
+        // Dropped digits round toward zero in both directions
+        assert_eq!(rescale(1_999, 9, 7), Ok(19));
+        assert_eq!(rescale(-1_999, 9, 7), Ok(-19));
+        assert_eq!(rescale(i128::MAX, 6, 7), Err(Error::MathOverflow));
+        assert_eq!(rescale(1, 0, 39), Err(Error::MathOverflow));
+        assert_eq!(rescale(1, 39, 0), Err(Error::MathOverflow));
+    }
+}